@@ -0,0 +1,66 @@
+use std::process::exit;
+
+use clap::ArgMatches;
+
+use client::Client;
+use provider;
+
+pub fn run(matches: &ArgMatches) {
+    let issue: u64 = match matches.value_of("issue").unwrap().parse() {
+        Ok(issue) => issue,
+        Err(_) => {
+            println!("Issue must be a number");
+            exit(1);
+        }
+    };
+
+    let token = matches.value_of("token").unwrap();
+    let user = matches.value_of("user").unwrap();
+    let repo = matches.value_of("repo").unwrap();
+    let endpoint = matches.value_of("endpoint").unwrap_or("https://api.github.com");
+    let provider = provider::from_name(matches.value_of("provider").unwrap_or("github"));
+
+    let to_add = label_names(matches.value_of("add"));
+    let to_remove = label_names(matches.value_of("remove"));
+
+    let client = Client::new(repo, token, user, endpoint, provider);
+
+    let current_labels = match client.issue_labels(issue) {
+        Ok(current_labels) => current_labels,
+        Err(error) => {
+            println!("Error getting labels for issue #{}: {:?}", issue, error);
+            exit(1);
+        }
+    };
+
+    let missing: Vec<String> = to_add.into_iter().filter(|name| {
+        !current_labels.iter().any(|label| &label.name == name)
+    }).collect();
+
+    if !missing.is_empty() {
+        match client.add_issue_labels(issue, &missing) {
+            Ok(_) => println!("ADD {}", missing.join(", ")),
+            Err(error) => println!("FAILURE {:?}", error),
+        }
+    }
+
+    let present: Vec<String> = to_remove.into_iter().filter(|name| {
+        current_labels.iter().any(|label| &label.name == name)
+    }).collect();
+
+    for name in &present {
+        match client.remove_issue_label(issue, name) {
+            Ok(_) => println!("REMOVE {}", name),
+            Err(error) => println!("FAILURE {:?}", error),
+        }
+    }
+}
+
+fn label_names(value: Option<&str>) -> Vec<String> {
+    match value {
+        Some(value) => value.split(',').map(|name| name.trim().to_string()).filter(|name| {
+            !name.is_empty()
+        }).collect(),
+        None => vec![],
+    }
+}
@@ -1,16 +1,22 @@
 use std::io::Error as IoError;
 use std::io::Read;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use hyper::Client as HyperClient;
 use hyper::Error as HyperError;
-use hyper::header::{Bearer, Authorization, Headers, UserAgent};
+use hyper::header::{Headers, UserAgent};
 use hyper::method::Method;
 use hyper::status::StatusCode;
 use rustc_serialize::json;
 use rustc_serialize::json::DecoderError;
-
+use url::percent_encoding::{percent_encode, PATH_SEGMENT_ENCODE_SET};
 
 use label::Label;
+use provider::Provider;
+
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+const MAX_BACKOFF_SECS: u64 = 60;
 
 #[derive(Debug)]
 pub enum Error {
@@ -32,107 +38,256 @@ impl From<IoError> for Error {
     }
 }
 
-pub struct Client<'a> {
+pub struct Client {
     client: HyperClient,
-    repo: &'a str,
-    token: &'a str,
-    user: &'a str,
-    endpoint: &'a str,
+    provider: Box<Provider>,
+    repo: String,
+    token: String,
+    user: String,
+    endpoint: String,
 }
 
-impl<'a> Client<'a> {
-    pub fn new(repo: &'a str, token: &'a str, user: &'a str, endpoint: &'a str) -> Client<'a> {
+impl Client {
+    pub fn new(repo: &str, token: &str, user: &str, endpoint: &str, provider: Box<Provider>) -> Client {
         Client {
             client: HyperClient::new(),
-            repo: repo,
-            token: token,
-            user: user,
-            endpoint: endpoint,
+            provider: provider,
+            repo: repo.to_string(),
+            token: token.to_string(),
+            user: user.to_string(),
+            endpoint: endpoint.to_string(),
         }
     }
 
-    pub fn create<'b>(&self, label: &'b Label) -> Result<(), Error> {
+    pub fn create(&self, label: &Label) -> Result<(), Error> {
         let data = self.to_json_string(label);
+        let url = format!("{}{}", self.endpoint, self.provider.labels_path(&self.user, &self.repo));
 
-        let mut response = try!(
-            self.client.post(
-                &format!("{}/repos/{}/{}/labels", self.endpoint, self.user, self.repo)
-            ).headers(self.headers()).body(&data).send()
-        );
+        let (status, body, _) = try!(self.send_with_retry(Method::Post, &url, Some(&data)));
 
-        let mut body = String::new();
-        try!(response.read_to_string(&mut body));
-
-        match response.status {
+        match status {
             StatusCode::Created => Ok(()),
-            _ => return Err(Error::NotOk(body)),
+            _ => Err(Error::NotOk(body)),
         }
     }
 
-    pub fn delete<'b>(&self, label: &'b Label) -> Result<(), Error> {
-        let url = label.url.to_string();
+    pub fn delete(&self, label: &Label) -> Result<(), Error> {
+        let (status, body, _) = try!(self.send_with_retry(Method::Delete, &label.url, None));
+
+        match status {
+            StatusCode::NoContent => Ok(()),
+            _ => Err(Error::NotOk(body)),
+        }
+    }
 
-        let mut response = try!(
-            self.client.delete(&url).headers(self.headers()).send()
+    pub fn list(&self) -> Result<Vec<Label>, Error> {
+        let mut labels = Vec::new();
+        let mut url = format!(
+            "{}{}?per_page=100", self.endpoint, self.provider.labels_path(&self.user, &self.repo)
         );
 
-        let mut body = String::new();
-        try!(response.read_to_string(&mut body));
+        loop {
+            let (status, body, headers) = try!(self.send_with_retry(Method::Get, &url, None));
 
-        match response.status {
-            StatusCode::NoContent => Ok(()),
-            _ => return Err(Error::NotOk(body)),
+            match status {
+                StatusCode::Ok => {},
+                _ => return Err(Error::NotOk(body)),
+            }
+
+            let page = match self.provider.decode_labels(&body) {
+                Ok(page) => page,
+                Err(error) => return Err(Error::Json(error)),
+            };
+
+            labels.extend(page);
+
+            match next_page_url(&headers) {
+                Some(next_url) => url = next_url,
+                None => break,
+            }
         }
+
+        Ok(labels)
     }
 
-    pub fn list<'b>(&self) -> Result<Vec<Label>, Error> {
-        let mut response =  try!(
-            self.client.get(
-                &format!("{}/repos/{}/{}/labels", self.endpoint, self.user, self.repo)
-            ).headers(self.headers()).send()
+    pub fn update(&self, label: &Label) -> Result<(), Error> {
+        let data = self.to_json_string(label);
+
+        let (status, body, _) = try!(self.send_with_retry(Method::Patch, &label.url, Some(&data)));
+
+        match status {
+            StatusCode::Ok => Ok(()),
+            _ => Err(Error::NotOk(body)),
+        }
+    }
+
+    pub fn issue_labels(&self, issue: u64) -> Result<Vec<Label>, Error> {
+        let url = format!(
+            "{}{}", self.endpoint, self.provider.issue_labels_path(&self.user, &self.repo, issue)
         );
 
-        let mut body = String::new();
-        try!(response.read_to_string(&mut body));
+        let (status, body, _) = try!(self.send_with_retry(Method::Get, &url, None));
 
-        match response.status {
+        match status {
             StatusCode::Ok => {},
             _ => return Err(Error::NotOk(body)),
         }
 
-        match json::decode(&body) {
+        match self.provider.decode_labels(&body) {
             Ok(labels) => Ok(labels),
             Err(error) => Err(Error::Json(error)),
         }
     }
 
-    pub fn update<'b>(&self, label: &'b Label) -> Result<(), Error> {
-        let url = label.url.to_string();
-        let data = self.to_json_string(label);
+    pub fn add_issue_labels(&self, issue: u64, names: &[String]) -> Result<(), Error> {
+        let url = format!(
+            "{}{}", self.endpoint, self.provider.issue_labels_path(&self.user, &self.repo, issue)
+        );
+
+        let payload = IssueLabelsPayload {
+            labels: names.iter().map(|name| &name[..]).collect(),
+        };
+        let data = json::encode(&payload).unwrap();
 
-        let mut response = try!(
-            self.client.request(Method::Patch, &url).headers(self.headers()).body(&data).send()
+        let (status, body, _) = try!(self.send_with_retry(Method::Post, &url, Some(&data)));
+
+        match status {
+            StatusCode::Ok | StatusCode::Created => Ok(()),
+            _ => Err(Error::NotOk(body)),
+        }
+    }
+
+    pub fn remove_issue_label(&self, issue: u64, name: &str) -> Result<(), Error> {
+        let encoded_name = percent_encode(name.as_bytes(), PATH_SEGMENT_ENCODE_SET);
+        let url = format!(
+            "{}{}/{}", self.endpoint, self.provider.issue_labels_path(&self.user, &self.repo, issue), encoded_name
         );
 
-        let mut body = String::new();
-        try!(response.read_to_string(&mut body));
+        let (status, body, _) = try!(self.send_with_retry(Method::Delete, &url, None));
 
-        match response.status {
-            StatusCode::Ok => Ok(()),
-            _ => return Err(Error::NotOk(body)),
+        match status {
+            StatusCode::Ok | StatusCode::NoContent => Ok(()),
+            _ => Err(Error::NotOk(body)),
+        }
+    }
+
+    // GitHub answers secondary rate limiting with 403 and primary rate
+    // limiting with 429, both of which carry a header telling us how long to
+    // wait before trying again. Honor that instead of failing the whole sync
+    // over a transient limit.
+    fn send_with_retry(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&str>,
+    ) -> Result<(StatusCode, String, Headers), Error> {
+        let mut attempts = 0;
+
+        loop {
+            let mut builder = self.client.request(method.clone(), url).headers(self.headers());
+
+            if let Some(data) = body {
+                builder = builder.body(data);
+            }
+
+            let mut response = try!(builder.send());
+
+            let mut response_body = String::new();
+            try!(response.read_to_string(&mut response_body));
+
+            let status = response.status;
+
+            if is_rate_limited(status) && attempts < MAX_RATE_LIMIT_RETRIES {
+                attempts += 1;
+                thread::sleep(retry_delay(&response.headers));
+                continue;
+            }
+
+            return Ok((status, response_body, response.headers));
         }
     }
 
     fn headers(&self) -> Headers {
         let mut headers = Headers::new();
 
-        headers.set(Authorization(Bearer { token: self.token.to_string() }));
+        let (auth_name, auth_value) = self.provider.auth_header(&self.token);
+        headers.set_raw(auth_name, vec![auth_value.into_bytes()]);
         headers.set(UserAgent(self.user.to_string()));
 
         headers
     }
 
     fn to_json_string<'b>(&self, label: &'b Label) -> String {
-        format!("{{\"name\": \"{}\",\"color\":\"{}\"}}", label.name, label.color)
+        let payload = LabelPayload {
+            name: &label.name,
+            color: &label.color,
+            description: label.description.as_ref().map(|description| &description[..]),
+        };
+
+        json::encode(&payload).unwrap()
     }
 }
+
+#[derive(RustcEncodable)]
+struct LabelPayload<'a> {
+    name: &'a str,
+    color: &'a str,
+    description: Option<&'a str>,
+}
+
+#[derive(RustcEncodable)]
+struct IssueLabelsPayload<'a> {
+    labels: Vec<&'a str>,
+}
+
+fn is_rate_limited(status: StatusCode) -> bool {
+    status.to_u16() == 403 || status.to_u16() == 429
+}
+
+fn retry_delay(headers: &Headers) -> Duration {
+    if let Some(seconds) = header_u64(headers, "Retry-After") {
+        return Duration::from_secs(seconds.min(MAX_BACKOFF_SECS));
+    }
+
+    if let Some(reset_at) = header_u64(headers, "X-RateLimit-Reset") {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        return Duration::from_secs(reset_at.saturating_sub(now).min(MAX_BACKOFF_SECS));
+    }
+
+    Duration::from_secs(1)
+}
+
+fn header_u64(headers: &Headers, name: &str) -> Option<u64> {
+    header_str(headers, name).and_then(|value| value.parse().ok())
+}
+
+fn header_str<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers.get_raw(name)
+        .and_then(|raw| raw.get(0))
+        .and_then(|bytes| ::std::str::from_utf8(bytes).ok())
+}
+
+// GitHub paginates the labels listing; a `Link` header of the form
+// `<url>; rel="next", <url>; rel="last"` points at the next page when one
+// exists. An absent header means the current page was the only one.
+fn next_page_url(headers: &Headers) -> Option<String> {
+    let value = match header_str(headers, "Link") {
+        Some(value) => value,
+        None => return None,
+    };
+
+    for part in value.split(',') {
+        let mut pieces = part.split(';');
+
+        let url = match pieces.next() {
+            Some(url) => url.trim().trim_matches(|c| c == '<' || c == '>'),
+            None => continue,
+        };
+
+        if pieces.any(|piece| piece.trim() == "rel=\"next\"") {
+            return Some(url.to_string());
+        }
+    }
+
+    None
+}
@@ -1,9 +1,18 @@
 use url::{ParseError, Url};
 
-#[derive(Debug, RustcDecodable)]
+use provider::Provider;
+
+// The shared, in-memory representation of a label, independent of which
+// provider it came from. GitHub's API responses decode into this directly;
+// Gitea/Forgejo's differ on the wire, so `Provider::decode_labels` maps
+// their own wire shape into this one instead of relying on it matching.
+#[derive(Clone, Debug, RustcDecodable)]
 pub struct Label {
     pub color: String,
+    pub description: Option<String>,
+    pub id: u64,
     pub name: String,
+    pub rename_from: Option<String>,
     pub url: String,
 }
 
@@ -34,17 +43,37 @@ impl Label {
         endpoint: &'a str,
         name: &'a str,
         color: &'a str,
+        description: Option<&'a str>,
+        rename_from: Option<&'a str>,
         user: &'a str,
-        repo: &'a str
+        repo: &'a str,
+        provider: &Provider,
     ) -> Result {
         let url = try!(
-            Url::parse(&format!("{}/repos/{}/{}/labels/{}", endpoint, user, repo, name))
+            Url::parse(&format!("{}{}/{}", endpoint, provider.labels_path(user, repo), name))
         );
 
         Ok(Label {
             color: color.to_string(),
+            description: description.map(|description| description.to_string()),
+            id: 0,
             name: name.to_string(),
+            rename_from: rename_from.map(|rename_from| rename_from.to_string()),
             url: url.to_string(),
         })
     }
+
+    // Builds the label to PATCH when renaming: the new name/color/description
+    // from the template, but the `id`/`url` of the label already on GitHub so
+    // the update targets it instead of creating a duplicate.
+    pub fn as_rename_of<'a>(&self, existing: &'a Label) -> Label {
+        Label {
+            color: self.color.clone(),
+            description: self.description.clone(),
+            id: existing.id,
+            name: self.name.clone(),
+            rename_from: self.rename_from.clone(),
+            url: existing.url.clone(),
+        }
+    }
 }
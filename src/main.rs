@@ -1,23 +1,32 @@
 extern crate clap;
+extern crate crypto;
 extern crate hyper;
 extern crate rustc_serialize;
 extern crate url;
 extern crate yaml_rust;
 
+mod apply;
 mod client;
 mod label;
+mod provider;
+mod serve;
 
+use std::cmp;
 use std::fs::File;
 use std::io::Error as IoError;
 use std::io::Read;
 use std::process::exit;
+use std::sync::Arc;
+use std::thread;
 
-use clap::{App, AppSettings, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 use yaml_rust::{Yaml, YamlLoader};
 
 use client::Client;
+use client::Error as ClientError;
 use label::Label;
 use label::Error as LabelError;
+use provider::Provider;
 
 fn main() {
     let matches = App::new("ghlabel")
@@ -30,26 +39,32 @@ Example:
 
     ghlabel --file labels.yml --token abc123 --user rust-lang --repo rust
 
-The file must contain an array of hashes, each with a name and a color. For
-example, here is a template for a subset of the default GitHub Issues labels:
+The file must contain an array of hashes, each with a name and a color, and
+optionally a description. For example, here is a template for a subset of the
+default GitHub Issues labels:
 
     - name: bug
       color: fc2929
+      description: Something isn't working
     - name: duplicate
       color: cccccc
     - name: enhancement
       color: 84b6eb
 
 By default, every label in the file will be created (or updated, if the color
-changed) on GitHub if it doesn't already exist and every label on GitHub not in
-the file will be deleted. Limit this behavior with the --no-create and
---no-delete flags, respectively. No output from the program indicates there
-were no changes made.
+or description changed) on GitHub if it doesn't already exist and every label
+on GitHub not in the file will be deleted. Limit this behavior with the
+--no-create and --no-delete flags, respectively. No output from the program
+indicates there were no changes made.
 
 An OAuth token can be obtained from https://github.com/settings/tokens.
 The token used requires the \"repo\" scope if the program will be run on a
 private repo. Otherwise, it only requires the \"public_repo\" scope.
 
+Run `ghlabel serve --help` for a long-lived webhook server that reconciles
+labels automatically whenever GitHub reports drift, or `ghlabel apply --help`
+to add or remove labels on a single issue.
+
 "
         )
         .arg(
@@ -97,6 +112,16 @@ private repo. Otherwise, it only requires the \"public_repo\" scope.
                 .required(false)
                 .empty_values(false)
         )
+        .arg(
+            Arg::with_name("provider")
+                .help("API provider to target: github or gitea (defaults to github)")
+                .long("provider")
+                .short("p")
+                .takes_value(true)
+                .required(false)
+                .possible_values(&["github", "gitea"])
+                .empty_values(false)
+        )
         .arg(
             Arg::with_name("dry-run")
                 .help("Print what the program would do without actually doing it")
@@ -113,17 +138,208 @@ private repo. Otherwise, it only requires the \"public_repo\" scope.
                 .help("Do not delete labels in the repo that are not in the file")
                 .long("no-delete")
         )
+        .arg(
+            Arg::with_name("concurrency")
+                .help("Number of label requests to run in flight at once (defaults to 8)")
+                .long("concurrency")
+                .short("c")
+                .takes_value(true)
+                .required(false)
+                .empty_values(false)
+        )
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Run a webhook server that reconciles labels whenever GitHub reports drift")
+                .arg(
+                    Arg::with_name("file")
+                        .help("Path to a YAML file containing the label template")
+                        .long("file")
+                        .short("f")
+                        .takes_value(true)
+                        .required(true)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .help("OAuth token for authenticating with GitHub")
+                        .long("token")
+                        .short("t")
+                        .takes_value(true)
+                        .required(true)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("user")
+                        .help("The name of the user or organization that owns the repository")
+                        .long("user")
+                        .short("u")
+                        .takes_value(true)
+                        .required(true)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("repo")
+                        .help("The name of the repository to apply the label template to")
+                        .long("repo")
+                        .short("r")
+                        .takes_value(true)
+                        .required(true)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("endpoint")
+                        .help("API endpoint to use (defaults to https://api.github.com)")
+                        .long("endpoint")
+                        .short("e")
+                        .takes_value(true)
+                        .required(false)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("provider")
+                        .help("API provider to target: github or gitea (defaults to github)")
+                        .long("provider")
+                        .short("p")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["github", "gitea"])
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("no-create")
+                        .help("Do not create labels missing from the repo but present in the file")
+                        .long("no-create")
+                )
+                .arg(
+                    Arg::with_name("no-delete")
+                        .help("Do not delete labels in the repo that are not in the file")
+                        .long("no-delete")
+                )
+                .arg(
+                    Arg::with_name("listen")
+                        .help("Address to listen on for webhook deliveries (defaults to 0.0.0.0:8080)")
+                        .long("listen")
+                        .short("l")
+                        .takes_value(true)
+                        .required(false)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("webhook-secret")
+                        .help("Secret configured on the GitHub webhook, used to verify delivery signatures")
+                        .long("webhook-secret")
+                        .takes_value(true)
+                        .required(true)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("concurrency")
+                        .help("Number of label requests to run in flight at once (defaults to 8)")
+                        .long("concurrency")
+                        .short("c")
+                        .takes_value(true)
+                        .required(false)
+                        .empty_values(false)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .about("Add or remove labels on a single issue")
+                .arg(
+                    Arg::with_name("issue")
+                        .help("Number of the issue to apply labels to")
+                        .index(1)
+                        .required(true)
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .help("OAuth token for authenticating with GitHub")
+                        .long("token")
+                        .short("t")
+                        .takes_value(true)
+                        .required(true)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("user")
+                        .help("The name of the user or organization that owns the repository")
+                        .long("user")
+                        .short("u")
+                        .takes_value(true)
+                        .required(true)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("repo")
+                        .help("The name of the repository the issue belongs to")
+                        .long("repo")
+                        .short("r")
+                        .takes_value(true)
+                        .required(true)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("endpoint")
+                        .help("API endpoint to use (defaults to https://api.github.com)")
+                        .long("endpoint")
+                        .short("e")
+                        .takes_value(true)
+                        .required(false)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("provider")
+                        .help("API provider to target: github or gitea (defaults to github)")
+                        .long("provider")
+                        .short("p")
+                        .takes_value(true)
+                        .required(false)
+                        .possible_values(&["github", "gitea"])
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("add")
+                        .help("Comma-separated label names to add to the issue")
+                        .long("add")
+                        .takes_value(true)
+                        .required(false)
+                        .empty_values(false)
+                )
+                .arg(
+                    Arg::with_name("remove")
+                        .help("Comma-separated label names to remove from the issue")
+                        .long("remove")
+                        .takes_value(true)
+                        .required(false)
+                        .empty_values(false)
+                )
+        )
         .get_matches();
 
+    if let Some(serve_matches) = matches.subcommand_matches("serve") {
+        serve::run(serve_matches);
+        return;
+    }
+
+    if let Some(apply_matches) = matches.subcommand_matches("apply") {
+        apply::run(apply_matches);
+        return;
+    }
+
     let path = matches.value_of("file").unwrap();
     let token = matches.value_of("token").unwrap();
     let user = matches.value_of("user").unwrap();
     let repo = matches.value_of("repo").unwrap();
     let endpoint = matches.value_of("endpoint").unwrap_or("https://api.github.com");
+    let provider = provider::from_name(matches.value_of("provider").unwrap_or("github"));
 
     let dry_run = matches.is_present("dry-run");
     let should_create = !matches.is_present("no-create");
     let should_delete = !matches.is_present("no-delete");
+    let concurrency = matches.value_of("concurrency")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_CONCURRENCY);
 
     let file_contents = match read_file(path) {
         Ok(contents) => contents,
@@ -133,20 +349,15 @@ private repo. Otherwise, it only requires the \"public_repo\" scope.
         }
     };
 
-    let yaml = match YamlLoader::load_from_str(&file_contents) {
-        Ok(yaml) => yaml,
+    let document = match parse_template(&file_contents) {
+        Ok(document) => document,
         Err(error) => {
-            println!("Failed to parse YAML data: {}", error);
+            println!("{}", error);
             exit(1);
         }
     };
 
-    if yaml.is_empty() {
-        println!("Expected labels.yml to have some data");
-        exit(1);
-    }
-
-    let template = match yaml[0].as_vec() {
+    let template = match document.as_vec() {
        Some(template) => template,
        None => {
            println!("Expect contents of labels.yml to be a single array");
@@ -154,7 +365,7 @@ private repo. Otherwise, it only requires the \"public_repo\" scope.
        }
     };
 
-    let labels = match get_labels(&template, user, repo) {
+    let labels = match get_labels(&template, endpoint, user, repo, &*provider) {
        Ok(labels) => labels,
        Err(_) => {
            println!("Invalid label! Each label must be a hash with the keys `name` and `color`");
@@ -162,88 +373,205 @@ private repo. Otherwise, it only requires the \"public_repo\" scope.
        }
     };
 
-    let client = Client::new(&repo, &token, &user, &endpoint);
+    let client = Arc::new(Client::new(&repo, &token, &user, &endpoint, provider));
 
-    let existing_labels = match client.list() {
-        Ok(existing_labels) => {
-            existing_labels
-        },
-        Err(error) => {
-            println!("Error getting existing labels from the GitHub API: {:?}", error);
-            exit(1);
-        },
-    };
+    if let Err(error) = reconcile(client, &labels, dry_run, should_create, should_delete, concurrency) {
+        println!("Error getting existing labels from the GitHub API: {:?}", error);
+        exit(1);
+    }
+}
+
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+enum Action {
+    Create(Label),
+    Rename(String, Label),
+    Update(Label),
+    Delete(Label),
+}
+
+pub fn reconcile(
+    client: Arc<Client>,
+    labels: &Vec<Label>,
+    dry_run: bool,
+    should_create: bool,
+    should_delete: bool,
+    concurrency: usize,
+) -> Result<(), ClientError> {
+    let existing_labels = try!(client.list());
+    let actions = plan_actions(labels, &existing_labels, should_create, should_delete);
+
+    run_actions(client, actions, dry_run, concurrency);
+
+    Ok(())
+}
+
+fn plan_actions(
+    labels: &Vec<Label>,
+    existing_labels: &Vec<Label>,
+    should_create: bool,
+    should_delete: bool,
+) -> Vec<Action> {
+    let mut actions = vec![];
+
+    // Computed independent of `should_create`: a rename source must stay
+    // protected from deletion even when `--no-create` means the rename
+    // itself won't be performed this run.
+    let renamed_names: Vec<String> = labels.iter().filter_map(|label| {
+        label.rename_from.as_ref().and_then(|old_name| {
+            existing_labels.iter()
+                .find(|existing_label| &existing_label.name == old_name)
+                .map(|_| old_name.clone())
+        })
+    }).collect();
 
     if should_create {
-        for label in &labels {
+        for label in labels {
             if existing_labels.contains(label) {
                 let existing_label = existing_labels.iter().find(|&existing_label| {
                     existing_label.name == label.name
                 }).unwrap();
 
-                if label.color != existing_label.color {
-                    if dry_run {
-                        println!("[DRY RUN] UPDATE {}: {}", label.name, label.color);
-                    } else {
-                        match client.update(&label) {
-                            Ok(_) => println!("UPDATE {}: {}", label.name, label.color),
-                            Err(error) => println!("FAILURE {:?}", error),
-                        }
-                    }
+                if label.color != existing_label.color || label.description != existing_label.description {
+                    actions.push(Action::Update(label.clone()));
                 }
-            } else {
-                if dry_run {
-                    println!("[DRY RUN] CREATE {}: {}", label.name, label.color);
-                } else {
-                    match client.create(&label) {
-                        Ok(_) => println!("CREATE {}: {}", label.name, label.color),
-                        Err(error) => println!("FAILURE {:?}", error),
-                    }
+            } else if let Some(old_name) = label.rename_from.as_ref() {
+                match existing_labels.iter().find(|existing_label| &existing_label.name == old_name) {
+                    Some(existing_label) => {
+                        actions.push(Action::Rename(old_name.clone(), label.as_rename_of(existing_label)));
+                    },
+                    None => actions.push(Action::Create(label.clone())),
                 }
+            } else {
+                actions.push(Action::Create(label.clone()));
             }
         }
     }
 
     if should_delete {
-        for existing_label in &existing_labels {
-            if !labels.contains(existing_label) {
-                if dry_run {
-                    println!("[DRY RUN] DELETE {}", existing_label.name);
-                } else {
-                    match client.delete(existing_label) {
-                        Ok(_) => println!("DELETE {}", existing_label.name),
-                        Err(error) => println!("FAILURE {:?}", error),
-                    }
-                }
+        for existing_label in existing_labels {
+            let is_renamed = renamed_names.contains(&existing_label.name);
+
+            if !labels.contains(existing_label) && !is_renamed {
+                actions.push(Action::Delete(existing_label.clone()));
             }
         }
     }
+
+    actions
+}
+
+// Labels are independent of one another, so requests for each can run
+// concurrently. Slow connections or a rate limit shouldn't serialize an
+// entire sync behind every prior label. Work is run in bounded batches
+// rather than with a single long-lived pool so no more than `concurrency`
+// requests are ever in flight at once.
+fn run_actions(client: Arc<Client>, mut actions: Vec<Action>, dry_run: bool, concurrency: usize) {
+    let batch_size = cmp::max(concurrency, 1);
+
+    while !actions.is_empty() {
+        let batch: Vec<Action> = actions.drain(..cmp::min(batch_size, actions.len())).collect();
+
+        let handles: Vec<_> = batch.into_iter().map(|action| {
+            let client = client.clone();
+
+            thread::spawn(move || perform_action(&client, action, dry_run))
+        }).collect();
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn perform_action(client: &Client, action: Action, dry_run: bool) {
+    match action {
+        Action::Create(label) => {
+            if dry_run {
+                println!("[DRY RUN] CREATE {}: {}", label.name, label.color);
+            } else {
+                match client.create(&label) {
+                    Ok(_) => println!("CREATE {}: {}", label.name, label.color),
+                    Err(error) => println!("FAILURE {:?}", error),
+                }
+            }
+        },
+        Action::Rename(old_name, label) => {
+            if dry_run {
+                println!("[DRY RUN] RENAME {} -> {}: {}", old_name, label.name, label.color);
+            } else {
+                match client.update(&label) {
+                    Ok(_) => println!("RENAME {} -> {}: {}", old_name, label.name, label.color),
+                    Err(error) => println!("FAILURE {:?}", error),
+                }
+            }
+        },
+        Action::Update(label) => {
+            if dry_run {
+                println!("[DRY RUN] UPDATE {}: {}", label.name, label.color);
+            } else {
+                match client.update(&label) {
+                    Ok(_) => println!("UPDATE {}: {}", label.name, label.color),
+                    Err(error) => println!("FAILURE {:?}", error),
+                }
+            }
+        },
+        Action::Delete(label) => {
+            if dry_run {
+                println!("[DRY RUN] DELETE {}", label.name);
+            } else {
+                match client.delete(&label) {
+                    Ok(_) => println!("DELETE {}", label.name),
+                    Err(error) => println!("FAILURE {:?}", error),
+                }
+            }
+        },
+    }
 }
 
-fn read_file<'a>(path: &'a str) -> Result<String, IoError> {
+pub fn read_file<'a>(path: &'a str) -> Result<String, IoError> {
     let mut f = try!(File::open(path));
     let mut s = String::new();
     try!(f.read_to_string(&mut s));
     Ok(s)
 }
 
-fn get_labels<'a>(
+pub fn parse_template<'a>(file_contents: &'a str) -> Result<Yaml, String> {
+    let yaml = match YamlLoader::load_from_str(file_contents) {
+        Ok(yaml) => yaml,
+        Err(error) => return Err(format!("Failed to parse YAML data: {}", error)),
+    };
+
+    if yaml.is_empty() {
+        return Err("Expected labels.yml to have some data".to_string());
+    }
+
+    Ok(yaml[0].clone())
+}
+
+pub fn get_labels<'a>(
     template: &'a Vec<Yaml>,
+    endpoint: &'a str,
     user: &'a str,
     repo: &'a str,
+    provider: &Provider,
 ) -> Result<Vec<Label>, LabelError> {
     let mut labels = vec![];
 
     for item in template.iter() {
-       let (name, color) = try!(get_name_and_color(item));
-       let label = try!(Label::new(name, color, user, repo));
+       let (name, color, description, rename_from) = try!(get_name_color_and_description(item));
+       let label = try!(
+           Label::new(endpoint, name, color, description, rename_from, user, repo, provider)
+       );
        labels.push(label);
     }
 
     Ok(labels)
 }
 
-pub fn get_name_and_color<'a>(yaml: &'a Yaml) -> Result<(&'a str, &'a str), LabelError> {
+pub fn get_name_color_and_description<'a>(
+    yaml: &'a Yaml
+) -> Result<(&'a str, &'a str, Option<&'a str>, Option<&'a str>), LabelError> {
     match yaml.as_hash() {
         Some(hash) => {
             let name = match hash[&Yaml::from_str("name")].as_str() {
@@ -256,8 +584,103 @@ pub fn get_name_and_color<'a>(yaml: &'a Yaml) -> Result<(&'a str, &'a str), Labe
                 None => return Err(LabelError::MissingColor)
             };
 
-            Ok((name, color))
+            let description = hash.get(&Yaml::from_str("description")).and_then(|d| d.as_str());
+            let rename_from = hash.get(&Yaml::from_str("rename_from")).and_then(|d| d.as_str());
+
+            Ok((name, color, description, rename_from))
         },
         None => Err(LabelError::YamlItemNotHash),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use label::Label;
+
+    use super::{plan_actions, Action};
+
+    fn label(name: &str, color: &str) -> Label {
+        Label {
+            color: color.to_string(),
+            description: None,
+            id: 0,
+            name: name.to_string(),
+            rename_from: None,
+            url: format!("https://api.github.com/repos/o/r/labels/{}", name),
+        }
+    }
+
+    fn renamed_label(name: &str, color: &str, rename_from: &str) -> Label {
+        let mut label = label(name, color);
+        label.rename_from = Some(rename_from.to_string());
+        label
+    }
+
+    #[test]
+    fn rename_target_already_exists_is_skipped() {
+        let existing = vec![label("old", "ededed"), label("new", "ededed")];
+        let templated = vec![renamed_label("new", "ededed", "old")];
+
+        let actions = plan_actions(&templated, &existing, true, false);
+
+        assert_eq!(actions.len(), 0);
+    }
+
+    #[test]
+    fn rename_source_missing_falls_back_to_create() {
+        let existing = vec![label("unrelated", "ededed")];
+        let templated = vec![renamed_label("new", "ededed", "old")];
+
+        let actions = plan_actions(&templated, &existing, true, false);
+
+        assert_eq!(actions.len(), 1);
+
+        match actions[0] {
+            Action::Create(ref label) => assert_eq!(label.name, "new"),
+            _ => panic!("expected a Create action"),
+        }
+    }
+
+    #[test]
+    fn color_only_change_is_a_normal_update() {
+        let existing = vec![label("bug", "ededed")];
+        let templated = vec![label("bug", "ff0000")];
+
+        let actions = plan_actions(&templated, &existing, true, false);
+
+        assert_eq!(actions.len(), 1);
+
+        match actions[0] {
+            Action::Update(ref label) => assert_eq!(label.color, "ff0000"),
+            _ => panic!("expected an Update action"),
+        }
+    }
+
+    #[test]
+    fn rename_source_is_excluded_from_deletion() {
+        let existing = vec![label("old", "ededed")];
+        let templated = vec![renamed_label("new", "ededed", "old")];
+
+        let actions = plan_actions(&templated, &existing, true, true);
+
+        assert_eq!(actions.len(), 1);
+
+        match actions[0] {
+            Action::Rename(ref old_name, ref label) => {
+                assert_eq!(old_name, "old");
+                assert_eq!(label.name, "new");
+            },
+            _ => panic!("expected a Rename action"),
+        }
+    }
+
+    #[test]
+    fn rename_source_is_protected_even_with_create_disabled() {
+        let existing = vec![label("old", "ededed")];
+        let templated = vec![renamed_label("new", "ededed", "old")];
+
+        let actions = plan_actions(&templated, &existing, false, true);
+
+        assert_eq!(actions.len(), 0);
+    }
+}
@@ -0,0 +1,135 @@
+use rustc_serialize::json;
+use rustc_serialize::json::DecoderError;
+
+use label::Label;
+
+// The API shapes exposed by GitHub and Gitea/Forgejo for managing labels are
+// close enough to share the same `Client`, but they diverge on the path
+// prefix, the auth header style, and the label JSON itself. This trait
+// isolates those differences so `Client` can stay written against a single,
+// generic labels API.
+pub trait Provider: Send + Sync {
+    fn labels_path(&self, user: &str, repo: &str) -> String;
+    fn issue_labels_path(&self, user: &str, repo: &str, issue: u64) -> String;
+    fn auth_header(&self, token: &str) -> (&'static str, String);
+    fn decode_labels(&self, body: &str) -> Result<Vec<Label>, DecoderError>;
+}
+
+pub struct GitHub;
+
+impl Provider for GitHub {
+    fn labels_path(&self, user: &str, repo: &str) -> String {
+        format!("/repos/{}/{}/labels", user, repo)
+    }
+
+    fn issue_labels_path(&self, user: &str, repo: &str, issue: u64) -> String {
+        format!("/repos/{}/{}/issues/{}/labels", user, repo, issue)
+    }
+
+    fn auth_header(&self, token: &str) -> (&'static str, String) {
+        ("Authorization", format!("Bearer {}", token))
+    }
+
+    fn decode_labels(&self, body: &str) -> Result<Vec<Label>, DecoderError> {
+        json::decode(body)
+    }
+}
+
+pub struct Gitea;
+
+impl Provider for Gitea {
+    fn labels_path(&self, user: &str, repo: &str) -> String {
+        format!("/api/v1/repos/{}/{}/labels", user, repo)
+    }
+
+    fn issue_labels_path(&self, user: &str, repo: &str, issue: u64) -> String {
+        format!("/api/v1/repos/{}/{}/issues/{}/labels", user, repo, issue)
+    }
+
+    fn auth_header(&self, token: &str) -> (&'static str, String) {
+        ("Authorization", format!("token {}", token))
+    }
+
+    fn decode_labels(&self, body: &str) -> Result<Vec<Label>, DecoderError> {
+        let wire: Vec<GiteaLabel> = try!(json::decode(body));
+        Ok(wire.into_iter().map(Label::from).collect())
+    }
+}
+
+// Gitea/Forgejo's label response always includes `description` as a plain
+// string (empty when unset) rather than GitHub's nullable field, and carries
+// extra fields (`exclusive`, `is_archived`) `Label` has no use for. Decoding
+// into this wire shape first and mapping it into `Label` keeps that
+// divergence out of the shared struct instead of hoping the two happen to
+// match.
+#[derive(RustcDecodable)]
+struct GiteaLabel {
+    id: u64,
+    name: String,
+    color: String,
+    description: String,
+    url: String,
+}
+
+impl From<GiteaLabel> for Label {
+    fn from(wire: GiteaLabel) -> Label {
+        Label {
+            color: wire.color,
+            description: if wire.description.is_empty() { None } else { Some(wire.description) },
+            id: wire.id,
+            name: wire.name,
+            rename_from: None,
+            url: wire.url,
+        }
+    }
+}
+
+pub fn from_name(name: &str) -> Box<Provider> {
+    match name {
+        "gitea" => Box::new(Gitea),
+        _ => Box::new(GitHub),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Gitea, GitHub, Provider};
+
+    #[test]
+    fn decodes_gitea_shaped_labels_with_no_description() {
+        let body = r#"[{
+            "id": 1,
+            "name": "bug",
+            "color": "fc2929",
+            "description": "",
+            "url": "https://gitea.example.com/api/v1/repos/o/r/labels/1",
+            "exclusive": false,
+            "is_archived": false
+        }]"#;
+
+        let labels = Gitea.decode_labels(body).unwrap();
+
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].id, 1);
+        assert_eq!(labels[0].name, "bug");
+        assert_eq!(labels[0].color, "fc2929");
+        assert_eq!(labels[0].description, None);
+        assert_eq!(labels[0].url, "https://gitea.example.com/api/v1/repos/o/r/labels/1");
+    }
+
+    #[test]
+    fn decodes_github_shaped_labels() {
+        let body = r#"[{
+            "id": 1,
+            "name": "bug",
+            "color": "fc2929",
+            "description": "Something isn't working",
+            "url": "https://api.github.com/repos/o/r/labels/bug"
+        }]"#;
+
+        let labels = GitHub.decode_labels(body).unwrap();
+
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].description, Some("Something isn't working".to_string()));
+    }
+}
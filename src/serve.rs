@@ -0,0 +1,217 @@
+use std::io::Read;
+use std::process::exit;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use clap::ArgMatches;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+use hyper::server::{Handler, Request, Response, Server};
+use hyper::status::StatusCode;
+use rustc_serialize::json::Json;
+
+use client::Client;
+use provider;
+
+pub fn run(matches: &ArgMatches) {
+    let handler = WebhookHandler {
+        path: matches.value_of("file").unwrap().to_string(),
+        token: matches.value_of("token").unwrap().to_string(),
+        user: matches.value_of("user").unwrap().to_string(),
+        repo: matches.value_of("repo").unwrap().to_string(),
+        endpoint: matches.value_of("endpoint").unwrap_or("https://api.github.com").to_string(),
+        provider_name: matches.value_of("provider").unwrap_or("github").to_string(),
+        webhook_secret: matches.value_of("webhook-secret").unwrap().to_string(),
+        should_create: !matches.is_present("no-create"),
+        should_delete: !matches.is_present("no-delete"),
+        concurrency: matches.value_of("concurrency")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(::DEFAULT_CONCURRENCY),
+    };
+
+    let listen = matches.value_of("listen").unwrap_or("0.0.0.0:8080");
+
+    let server = match Server::http(listen) {
+        Ok(server) => server,
+        Err(error) => {
+            println!("Failed to bind webhook server on {}: {}", listen, error);
+            exit(1);
+        }
+    };
+
+    match server.handle(handler) {
+        Ok(listening) => {
+            println!("Listening for webhook deliveries on {}", listening.socket);
+
+            loop {
+                thread::sleep(Duration::from_secs(3600));
+            }
+        },
+        Err(error) => {
+            println!("Failed to start webhook server: {}", error);
+            exit(1);
+        }
+    }
+}
+
+struct WebhookHandler {
+    path: String,
+    token: String,
+    user: String,
+    repo: String,
+    endpoint: String,
+    provider_name: String,
+    webhook_secret: String,
+    should_create: bool,
+    should_delete: bool,
+    concurrency: usize,
+}
+
+impl Handler for WebhookHandler {
+    fn handle(&self, mut req: Request, res: Response) {
+        let event = header_value(&req, "X-GitHub-Event");
+        let signature = header_value(&req, "X-Hub-Signature-256");
+
+        let mut body = Vec::new();
+        if req.read_to_end(&mut body).is_err() {
+            respond(res, StatusCode::BadRequest);
+            return;
+        }
+
+        let verified = match signature {
+            Some(ref signature) => verify_signature(&self.webhook_secret, &body, signature),
+            None => false,
+        };
+
+        if !verified {
+            respond(res, StatusCode::Unauthorized);
+            return;
+        }
+
+        let is_configured_repo = match ::std::str::from_utf8(&body).ok().and_then(|body| Json::from_str(body).ok()) {
+            Some(payload) => repository_full_name(&payload) == Some(format!("{}/{}", self.user, self.repo)),
+            None => false,
+        };
+
+        if is_configured_repo {
+            match event.as_ref().map(|event| &event[..]) {
+                Some("label") | Some("push") => self.reconcile(),
+                _ => {},
+            }
+        }
+
+        respond(res, StatusCode::Ok);
+    }
+}
+
+impl WebhookHandler {
+    fn reconcile(&self) {
+        let file_contents = match ::read_file(&self.path) {
+            Ok(contents) => contents,
+            Err(error) => {
+                println!("Failed to read labels.yml: {}", error);
+                return;
+            }
+        };
+
+        let document = match ::parse_template(&file_contents) {
+            Ok(document) => document,
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        };
+
+        let template = match document.as_vec() {
+            Some(template) => template,
+            None => {
+                println!("Expect contents of labels.yml to be a single array");
+                return;
+            }
+        };
+
+        let provider = provider::from_name(&self.provider_name);
+
+        let labels = match ::get_labels(template, &self.endpoint, &self.user, &self.repo, &*provider) {
+            Ok(labels) => labels,
+            Err(_) => {
+                println!("Invalid label! Each label must be a hash with the keys `name` and `color`");
+                return;
+            }
+        };
+
+        let client = Arc::new(Client::new(&self.repo, &self.token, &self.user, &self.endpoint, provider));
+
+        match ::reconcile(client, &labels, false, self.should_create, self.should_delete, self.concurrency) {
+            Ok(_) => {},
+            Err(error) => println!("Error reconciling labels: {:?}", error),
+        }
+    }
+}
+
+// Webhook secrets are commonly shared across every repo in an org, so a
+// verified signature alone doesn't mean this delivery is about the repo
+// this server was configured to reconcile.
+fn repository_full_name(payload: &Json) -> Option<String> {
+    payload.find_path(&["repository", "full_name"])
+        .and_then(|value| value.as_string())
+        .map(|value| value.to_string())
+}
+
+fn header_value(req: &Request, name: &str) -> Option<String> {
+    req.headers.get_raw(name)
+        .and_then(|raw| raw.get(0))
+        .and_then(|bytes| ::std::str::from_utf8(bytes).ok())
+        .map(|value| value.to_string())
+}
+
+fn respond(mut res: Response, status: StatusCode) {
+    *res.status_mut() = status;
+    let _ = res.send(b"");
+}
+
+// GitHub signs webhook deliveries as `sha256=<hex hmac>` over the raw request
+// body. Recomputing the HMAC and comparing in constant time keeps us from
+// leaking timing information that would help an attacker forge deliveries.
+fn verify_signature(secret: &str, body: &[u8], header: &str) -> bool {
+    let hex_signature = if header.starts_with("sha256=") {
+        &header["sha256=".len()..]
+    } else {
+        return false;
+    };
+
+    let expected = match hex_decode(hex_signature) {
+        Some(expected) => expected,
+        None => return false,
+    };
+
+    let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+    mac.input(body);
+
+    fixed_time_eq(mac.result().code(), &expected)
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(hex.len() / 2);
+
+    for chunk in hex.as_bytes().chunks(2) {
+        let byte_str = match ::std::str::from_utf8(chunk) {
+            Ok(byte_str) => byte_str,
+            Err(_) => return None,
+        };
+
+        match u8::from_str_radix(byte_str, 16) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => return None,
+        }
+    }
+
+    Some(bytes)
+}